@@ -1,18 +1,30 @@
 extern crate reqwest;
 extern crate serde_json;
+extern crate chacha20;
+extern crate poly1305;
+extern crate rand;
+extern crate base64;
+extern crate redis;
+extern crate subtle;
+
+use redis::Commands;
 
 use serde_json::value::Value as JsonValue;
 
+use chacha20::ChaCha20;
+use chacha20::cipher::{NewCipher, StreamCipher, StreamCipherSeek};
+use poly1305::{Poly1305, universal_hash::NewUniversalHash, universal_hash::UniversalHash};
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+
 // Networking
-use std::{net, thread, time, collections};
+use std::{net, thread, time, collections, sync};
 use std::io::{Read, Write};
+use std::sync::mpsc;
 
 // String
 use std::str;
 
-//Display
-use std::fmt;
-
 // Input
 use helpers::input;
 
@@ -22,6 +34,54 @@ use protocol::Message;
 // Retry attempts for http connection
 const RETRY_ATTEMPTS_HTTP: i32 = 5;
 
+// EncryptedConnection framing
+const CHACHA_NONCE_LEN: usize = 12;
+const POLY1305_TAG_LEN: usize = 16;
+
+// TcpConnection framing: 4-byte big-endian length prefix
+const FRAME_LENGTH_PREFIX_BYTES: usize = 4;
+
+// Reject a frame header before allocating for it, so a peer can't force an
+// arbitrarily large up-front allocation just by claiming a huge length.
+// `pub(crate)` so `game_server`'s mio-based framing can enforce the same
+// cap instead of redefining it and risking the two drifting apart.
+pub(crate) const MAX_FRAME_PAYLOAD_BYTES: usize = 1024 * 1024;
+
+/// Write a single length-prefixed frame: a 4-byte big-endian length
+/// followed by exactly that many payload bytes.
+fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), String> {
+  let len = payload.len() as u32;
+
+  writer.write_all(&len.to_be_bytes())
+    .map_err(|err| format!("Framing > Could not write frame header: {}", err))?;
+
+  writer.write_all(payload)
+    .map_err(|err| format!("Framing > Could not write frame payload: {}", err))
+}
+
+/// Read a single length-prefixed frame. Returns `Err` on a truncated
+/// header or payload rather than panicking, unlike the old `\r\n`/512-byte
+/// reader it replaces.
+fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>, String> {
+  let mut header = [0u8; FRAME_LENGTH_PREFIX_BYTES];
+
+  reader.read_exact(&mut header)
+    .map_err(|err| format!("Framing > Could not read frame header: {}", err))?;
+
+  let len = u32::from_be_bytes(header) as usize;
+
+  if len > MAX_FRAME_PAYLOAD_BYTES {
+    return Err(format!("Framing > Frame of {} bytes exceeds the {}-byte limit", len, MAX_FRAME_PAYLOAD_BYTES));
+  }
+
+  let mut payload = vec![0u8; len];
+
+  reader.read_exact(&mut payload)
+    .map_err(|err| format!("Framing > Could not read frame payload: {}", err))?;
+
+  Ok(payload)
+}
+
 
 
 
@@ -67,21 +127,6 @@ pub struct TcpConnection {
   stream: net::TcpStream,
 }
 
-enum TcpConnectionDelimiter {
-  EndOfMessage,
-}
-
-impl fmt::Display for TcpConnectionDelimiter {
-  //
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    let _ = match *self {
-      TcpConnectionDelimiter::EndOfMessage => write!(f, "\r\n"), // Just like HTTP
-    };
-
-    Ok(())
-  }
-}
-
 impl TcpConnection {
   ///
   pub fn new(host: &str) -> Result<TcpConnection, String> {
@@ -124,16 +169,9 @@ impl TcpConnection {
 impl Connection for TcpConnection {
   ///
   fn send_message(&mut self, message: &str) -> bool {
-    let mut data = String::from(message);
-
-    // Append the end of message delimiter
-    data.push_str(&TcpConnectionDelimiter::EndOfMessage.to_string());
-
-    let mut raw_data = data.as_bytes();
+    println!("TcpConnection > Sending {}", message);
 
-    println!("TcpConnection > Sending {}", str::from_utf8(&raw_data).unwrap());
-    
-    match self.stream.write(&mut raw_data) {
+    match write_frame(&mut self.stream, message.as_bytes()) {
       Ok(_) => true,
       Err(err) => {
         println!("TcpConnection > Writing error: {}", err);
@@ -144,34 +182,14 @@ impl Connection for TcpConnection {
 
   /// Receive a message from another player
   fn wait_for_message(&mut self) -> Result<String, String> {
-    let mut message = String::new();
-
-    while !message.contains(&TcpConnectionDelimiter::EndOfMessage.to_string()) {
-      let mut buffer = [0u8; 512];
-      
-      match self.stream.read(&mut buffer) {
-        Ok(_) => (),
-        Err(err) => panic!("Connection error: {}", err),
-      };
-
-      match str::from_utf8(&buffer) {
-        Ok(data) => message.push_str(&data),
-        Err(err) => panic!("Decoding error: {}", err),
-      };
+    let payload = read_frame(&mut self.stream)?;
 
-      println!("TcpConnection > Got message: {}", String::from_utf8_lossy(&buffer));
+    let message = str::from_utf8(&payload)
+      .map_err(|err| format!("TcpConnection > Decoding error: {}", err))?;
 
-      // If we receive an empty message, usually the connection is terminated.
-      if buffer[0] as char == '\0' {
-        break;
-      }
-    }
+    println!("TcpConnection > Got message: {}", message);
 
-    let message = message
-      .replace('\0', "")
-      .replace(&TcpConnectionDelimiter::EndOfMessage.to_string(), "");
-
-    Ok(message)
+    Ok(String::from(message))
   }
 
   ///
@@ -318,8 +336,925 @@ impl Connection for HttpConnection {
   }
 }
 
+// How often the subscriber thread's blocking read times out to check whether it's been asked to stop.
+const SUBSCRIBE_POLL_INTERVAL_MS: u64 = 500;
+
+/// Tag a message with the ply number it was published under.
+fn encode_envelope(seq: i64, message: &str) -> String {
+  format!("{}:{}", seq, message)
+}
+
+/// Inverse of `encode_envelope`.
+fn decode_envelope(envelope: &str) -> Result<(i64, String), String> {
+  let (seq, message) = envelope.split_once(':')
+    .ok_or_else(|| format!("RedisConnection > Malformed envelope: {}", envelope))?;
+
+  let seq = seq.parse::<i64>()
+    .map_err(|err| format!("RedisConnection > Bad sequence number in envelope {}: {}", envelope, err))?;
+
+  Ok((seq, String::from(message)))
+}
+
+/// True if `seq` is already covered by the catch-up snapshot read at connect time.
+fn dedupe_catch_up(catch_up_seq: &mut Option<i64>, seq: i64) -> bool {
+  match *catch_up_seq {
+    Some(catch_up) if seq <= catch_up => true,
+    _ => {
+      *catch_up_seq = None;
+      false
+    },
+  }
+}
+
+/// Redis connection
+pub struct RedisConnection {
+  client: redis::Client,
+  publish_channel: String,
+  state_key: String,
+  seq_key: String,
+  last_message: String,
+  catch_up_seq: Option<i64>,
+  incoming: mpsc::Receiver<String>,
+  stop: sync::Arc<sync::atomic::AtomicBool>,
+  subscriber: Option<thread::JoinHandle<()>>,
+}
+
+impl RedisConnection {
+  /// Open a connection to `redis_url`, subscribe for `game_id`/`color`, and catch up on `state_key`.
+  pub fn new(redis_url: &str, game_id: &str, color: &str) -> Result<RedisConnection, String> {
+    let opponent_color = match color {
+      "white" => "black",
+      "black" => "white",
+      other => return Err(format!("RedisConnection > Unknown color: {}", other)),
+    };
+
+    let client = redis::Client::open(redis_url)
+      .map_err(|err| format!("RedisConnection > Could not open {}: {}", redis_url, err))?;
+
+    let subscribe_channel = format!("game:{}:to_{}", game_id, color);
+    let state_key = format!("game:{}:state", game_id);
+    let seq_key = format!("game:{}:seq", game_id);
+
+    let (ready_sender, ready_receiver) = mpsc::channel();
+    let (message_sender, message_receiver) = mpsc::channel();
+
+    let stop = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+    let subscriber_stop = stop.clone();
+    let subscriber_client = client.clone();
+
+    let subscriber = thread::spawn(move || {
+      subscribe_loop(subscriber_client, subscribe_channel, subscriber_stop, ready_sender, message_sender);
+    });
+
+    ready_receiver.recv()
+      .map_err(|err| format!("RedisConnection > Subscriber thread died before subscribing: {}", err))?
+      .map_err(|err| format!("RedisConnection > Could not subscribe: {}", err))?;
+
+    let state_envelope: String = client.get_connection()
+      .map_err(|err| format!("RedisConnection > Could not connect: {}", err))?
+      .get(&state_key)
+      .unwrap_or_default();
+
+    let (last_message, catch_up_seq) = if state_envelope.is_empty() {
+      (String::new(), None)
+    } else {
+      let (seq, message) = decode_envelope(&state_envelope)?;
+      (message, Some(seq))
+    };
+
+    Ok(RedisConnection{
+      client,
+      publish_channel: format!("game:{}:to_{}", game_id, opponent_color),
+      state_key,
+      seq_key,
+      last_message,
+      catch_up_seq,
+      incoming: message_receiver,
+      stop,
+      subscriber: Some(subscriber),
+    })
+  }
+}
+
+/// Run on a background thread for the lifetime of a `RedisConnection`: subscribe once and forward every message to `messages`.
+fn subscribe_loop(client: redis::Client, channel: String, stop: sync::Arc<sync::atomic::AtomicBool>, ready: mpsc::Sender<Result<(), String>>, messages: mpsc::Sender<String>) {
+  let mut connection = match client.get_connection() {
+    Ok(connection) => connection,
+    Err(err) => {
+      let _ = ready.send(Err(format!("Could not connect: {}", err)));
+      return;
+    },
+  };
+
+  if let Err(err) = connection.set_read_timeout(Some(time::Duration::from_millis(SUBSCRIBE_POLL_INTERVAL_MS))) {
+    let _ = ready.send(Err(format!("Could not set read timeout: {}", err)));
+    return;
+  }
+
+  let mut pubsub = connection.as_pubsub();
+
+  if let Err(err) = pubsub.subscribe(&channel) {
+    let _ = ready.send(Err(format!("Could not subscribe to {}: {}", channel, err)));
+    return;
+  }
+
+  let _ = ready.send(Ok(()));
+
+  loop {
+    if stop.load(sync::atomic::Ordering::Relaxed) {
+      return;
+    }
+
+    let message = match pubsub.get_message() {
+      Ok(message) => message,
+      Err(err) if err.is_timeout() => continue,
+      Err(err) => {
+        println!("RedisConnection > Subscriber thread lost its subscription: {}", err);
+        return;
+      },
+    };
+
+    let payload: String = match message.get_payload() {
+      Ok(payload) => payload,
+      Err(err) => {
+        println!("RedisConnection > Bad payload: {}", err);
+        continue;
+      },
+    };
+
+    // The RedisConnection was dropped, so there's nowhere left to send.
+    if messages.send(payload).is_err() {
+      return;
+    }
+  }
+}
+
+impl Connection for RedisConnection {
+  /// Publish the message to the opponent's channel and record it as the game's latest state.
+  fn send_message(&mut self, message: &str) -> bool {
+    let mut connection = match self.client.get_connection() {
+      Ok(connection) => connection,
+      Err(err) => {
+        println!("RedisConnection > Could not connect: {}", err);
+        return false;
+      },
+    };
+
+    let seq: i64 = match connection.incr(&self.seq_key, 1) {
+      Ok(seq) => seq,
+      Err(err) => {
+        println!("RedisConnection > Could not reserve a sequence number: {}", err);
+        return false;
+      },
+    };
+
+    let envelope = encode_envelope(seq, message);
+
+    if let Err(err) = connection.publish::<_, _, i32>(&self.publish_channel, &envelope) {
+      println!("RedisConnection > Publish error: {}", err);
+      return false;
+    }
+
+    if let Err(err) = connection.set::<_, _, ()>(&self.state_key, &envelope) {
+      println!("RedisConnection > Could not record state: {}", err);
+    }
+
+    true
+  }
+
+  /// Block until the next move arrives from the background subscriber thread, skipping stale catch-up deliveries.
+  fn wait_for_message(&mut self) -> Result<String, String> {
+    loop {
+      let envelope = self.incoming.recv()
+        .map_err(|err| format!("RedisConnection > Subscription closed: {}", err))?;
+
+      let (seq, message) = decode_envelope(&envelope)?;
+
+      if dedupe_catch_up(&mut self.catch_up_seq, seq) {
+        continue;
+      }
+
+      self.last_message = message.clone();
+
+      return Ok(message);
+    }
+  }
+
+  fn get_message(&self) -> Result<String, String> {
+    Ok(self.last_message.clone())
+  }
+}
+
+impl Drop for RedisConnection {
+  /// Signal the background subscriber thread to stop and join it.
+  fn drop(&mut self) {
+    self.stop.store(true, sync::atomic::Ordering::Relaxed);
+
+    if let Some(subscriber) = self.subscriber.take() {
+      let _ = subscriber.join();
+    }
+  }
+}
+
+/// Encrypted connection
+pub struct EncryptedConnection<C: Connection> {
+  inner: C,
+  key: [u8; 32],
+  send_nonce_base: [u8; CHACHA_NONCE_LEN],
+  recv_nonce_base: [u8; CHACHA_NONCE_LEN],
+  send_counter: u64,
+  recv_counter: u64,
+}
+
+impl<C: Connection> EncryptedConnection<C> {
+  /// Wrap `inner` with authenticated encryption under the given pre-shared key.
+  pub fn new(mut inner: C, key: [u8; 32]) -> Result<EncryptedConnection<C>, String> {
+    let mut local_nonce = [0u8; CHACHA_NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut local_nonce);
+
+    if !inner.send_message(&base64::encode(&local_nonce)) {
+      return Err(String::from("EncryptedConnection > Failed to send handshake nonce"));
+    }
+
+    let peer_nonce_encoded = inner.wait_for_message()?;
+
+    let peer_nonce_bytes = base64::decode(peer_nonce_encoded.trim())
+      .map_err(|err| format!("EncryptedConnection > Bad handshake nonce: {}", err))?;
+
+    if peer_nonce_bytes.len() != CHACHA_NONCE_LEN {
+      return Err(String::from("EncryptedConnection > Handshake nonce had the wrong length"));
+    }
+
+    let mut peer_nonce = [0u8; CHACHA_NONCE_LEN];
+    peer_nonce.copy_from_slice(&peer_nonce_bytes);
+
+    Ok(EncryptedConnection{
+      inner,
+      key,
+      send_nonce_base: local_nonce,
+      recv_nonce_base: peer_nonce,
+      send_counter: 0,
+      recv_counter: 0,
+    })
+  }
+
+  /// Fold the message counter into the exchanged base nonce.
+  fn frame_nonce(base: &[u8; CHACHA_NONCE_LEN], counter: u64) -> [u8; CHACHA_NONCE_LEN] {
+    let mut nonce = *base;
+    let counter_bytes = counter.to_be_bytes();
+
+    for i in 0..8 {
+      nonce[4 + i] ^= counter_bytes[i];
+    }
+
+    nonce
+  }
+
+  /// The RFC 8439 AEAD trailing MAC block: 8 bytes of AAD length (always 0) then the ciphertext length.
+  fn mac_length_block(ciphertext_len: usize) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[8..16].copy_from_slice(&(ciphertext_len as u64).to_le_bytes());
+    block
+  }
+
+  /// Seal a plaintext message: derive the one-time Poly1305 key from the
+  /// first ChaCha20 block, encrypt starting at block 1 per RFC 8439, then
+  /// MAC the ciphertext.
+  fn seal(&self, plaintext: &[u8], nonce: &[u8; CHACHA_NONCE_LEN]) -> Vec<u8> {
+    let mut cipher = ChaCha20::new(self.key.as_ref().into(), nonce.as_ref().into());
+
+    let mut poly_key = [0u8; 32];
+    cipher.apply_keystream(&mut poly_key);
+    cipher.seek(64);
+
+    let mut ciphertext = plaintext.to_vec();
+    cipher.apply_keystream(&mut ciphertext);
+
+    let mut mac = Poly1305::new(poly_key.as_ref().into());
+    mac.update_padded(&ciphertext);
+    mac.update_padded(&Self::mac_length_block(ciphertext.len()));
+    let tag = mac.finalize();
+
+    let mut frame = Vec::with_capacity(CHACHA_NONCE_LEN + ciphertext.len() + POLY1305_TAG_LEN);
+    frame.extend_from_slice(nonce);
+    frame.extend_from_slice(&ciphertext);
+    frame.extend_from_slice(tag.into_bytes().as_slice());
+
+    frame
+  }
+
+  /// Verify and open a sealed frame against the expected nonce.
+  fn open(&self, frame: &[u8], expected_nonce: &[u8; CHACHA_NONCE_LEN]) -> Result<Vec<u8>, String> {
+    if frame.len() < CHACHA_NONCE_LEN + POLY1305_TAG_LEN {
+      return Err(String::from("EncryptedConnection > Frame too short to contain a nonce and tag"));
+    }
+
+    let (nonce_bytes, rest) = frame.split_at(CHACHA_NONCE_LEN);
+    let (ciphertext, tag_bytes) = rest.split_at(rest.len() - POLY1305_TAG_LEN);
+
+    // Constant-time comparison to avoid a timing side channel.
+    if !bool::from(nonce_bytes.ct_eq(expected_nonce.as_ref())) {
+      return Err(String::from("EncryptedConnection > Frame nonce did not match the expected sequence, frame rejected"));
+    }
+
+    let mut cipher = ChaCha20::new(self.key.as_ref().into(), expected_nonce.as_ref().into());
+
+    let mut poly_key = [0u8; 32];
+    cipher.apply_keystream(&mut poly_key);
+    cipher.seek(64);
+
+    let mut mac = Poly1305::new(poly_key.as_ref().into());
+    mac.update_padded(ciphertext);
+    mac.update_padded(&Self::mac_length_block(ciphertext.len()));
+    let expected_tag = mac.finalize();
+
+    if !bool::from(expected_tag.into_bytes().as_slice().ct_eq(tag_bytes)) {
+      return Err(String::from("EncryptedConnection > Poly1305 tag mismatch, frame rejected"));
+    }
+
+    let mut plaintext = ciphertext.to_vec();
+    cipher.apply_keystream(&mut plaintext);
+
+    Ok(plaintext)
+  }
+}
+
+impl<C: Connection> Connection for EncryptedConnection<C> {
+  /// Seal the message and hand the base64-encoded frame to the inner
+  /// connection, incrementing the nonce counter so it is never reused.
+  fn send_message(&mut self, message: &str) -> bool {
+    let nonce = Self::frame_nonce(&self.send_nonce_base, self.send_counter);
+    let frame = self.seal(message.as_bytes(), &nonce);
+
+    self.send_counter += 1;
+
+    self.inner.send_message(&base64::encode(&frame))
+  }
+
+  /// Decode, verify and open a frame from the inner connection, rejecting
+  /// it outright on a Poly1305 mismatch before any decryption happens.
+  fn wait_for_message(&mut self) -> Result<String, String> {
+    let encoded = self.inner.wait_for_message()?;
+
+    let frame = base64::decode(encoded.trim())
+      .map_err(|err| format!("EncryptedConnection > Bad frame encoding: {}", err))?;
+
+    let expected_nonce = Self::frame_nonce(&self.recv_nonce_base, self.recv_counter);
+    let plaintext = self.open(&frame, &expected_nonce)?;
+
+    self.recv_counter += 1;
+
+    String::from_utf8(plaintext)
+      .map_err(|err| format!("EncryptedConnection > Decoded frame was not valid UTF-8: {}", err))
+  }
+
+  fn get_message(&self) -> Result<String, String> {
+    self.inner.get_message()
+  }
+}
+
+/// Identifies one logical sub-stream of a `MuxConnection`.
+pub type StreamId = u16;
+
+// Stream 0 is reserved for the existing `protocol::Message` move traffic.
+const MUX_STREAM_MOVES: StreamId = 0;
+
+// Close a stream once its inbound queue grows past this many frames.
+const MUX_MAX_QUEUED_FRAMES: usize = 64;
+
+/// What a sub-stream is for, announced in the SYN frame's payload.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamKind {
+  /// Stream 0, pre-opened by `MuxConnection::new`, never sent in a SYN frame.
+  Moves,
+  Chat,
+  Spectator,
+  /// Any kind byte this version doesn't recognize.
+  Other(u8),
+}
+
+impl StreamKind {
+  fn to_byte(self) -> u8 {
+    match self {
+      StreamKind::Moves => 0,
+      StreamKind::Chat => 1,
+      StreamKind::Spectator => 2,
+      StreamKind::Other(byte) => byte,
+    }
+  }
+
+  fn from_byte(byte: u8) -> StreamKind {
+    match byte {
+      1 => StreamKind::Chat,
+      2 => StreamKind::Spectator,
+      other => StreamKind::Other(other),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MuxFlag {
+  Syn,
+  Data,
+  Fin,
+}
+
+impl MuxFlag {
+  fn to_byte(self) -> u8 {
+    match self {
+      MuxFlag::Syn => 0,
+      MuxFlag::Data => 1,
+      MuxFlag::Fin => 2,
+    }
+  }
+
+  fn from_byte(byte: u8) -> Result<MuxFlag, String> {
+    match byte {
+      0 => Ok(MuxFlag::Syn),
+      1 => Ok(MuxFlag::Data),
+      2 => Ok(MuxFlag::Fin),
+      other => Err(format!("MuxConnection > Unknown frame flag: {}", other)),
+    }
+  }
+}
+
+/// Inbound state for one logical sub-stream: what it's for, queued
+/// payloads waiting to be `recv`'d, and whether the other side has sent
+/// FIN.
+struct MuxStream {
+  kind: StreamKind,
+  inbound: collections::VecDeque<Vec<u8>>,
+  closed: bool,
+}
+
+impl MuxStream {
+  fn new(kind: StreamKind) -> MuxStream {
+    MuxStream{kind, inbound: collections::VecDeque::new(), closed: false}
+  }
+}
+
+/// Multiplexed connection
+pub struct MuxConnection<C: Connection> {
+  inner: C,
+  streams: collections::HashMap<StreamId, MuxStream>,
+  next_stream_id: StreamId,
+}
+
+impl<C: Connection> MuxConnection<C> {
+  /// Wrap `inner`, pre-opening the reserved moves stream (0). `is_initiator`
+  /// picks which half of the stream-id space this side allocates from.
+  pub fn new(inner: C, is_initiator: bool) -> MuxConnection<C> {
+    let mut streams = collections::HashMap::new();
+    streams.insert(MUX_STREAM_MOVES, MuxStream::new(StreamKind::Moves));
+
+    MuxConnection{
+      inner,
+      streams,
+      next_stream_id: if is_initiator { 1 } else { 2 },
+    }
+  }
+
+  /// Open a new logical sub-stream of the given kind and announce it to the other side with a SYN frame.
+  pub fn open_stream(&mut self, kind: StreamKind) -> Result<StreamId, String> {
+    let stream_id = self.next_stream_id;
+    self.next_stream_id += 2;
+
+    self.streams.insert(stream_id, MuxStream::new(kind));
+    self.write_frame(stream_id, MuxFlag::Syn, &[kind.to_byte()])?;
+
+    Ok(stream_id)
+  }
+
+  /// The kind of `stream_id`, if it has been opened locally or announced by
+  /// the peer's SYN frame. A stream first seen in a DATA frame, with no SYN
+  /// before it, reports `StreamKind::Other(0)`.
+  pub fn stream_kind(&self, stream_id: StreamId) -> Option<StreamKind> {
+    self.streams.get(&stream_id).map(|stream| stream.kind)
+  }
+
+  /// Send a payload on `stream_id`.
+  pub fn send(&mut self, stream_id: StreamId, payload: &[u8]) -> Result<(), String> {
+    self.write_frame(stream_id, MuxFlag::Data, payload)
+  }
+
+  /// Close `stream_id`, telling the other side no more data is coming.
+  pub fn close_stream(&mut self, stream_id: StreamId) -> Result<(), String> {
+    self.write_frame(stream_id, MuxFlag::Fin, &[])
+  }
+
+  /// Receive the next payload queued for `stream_id`, pumping the underlying connection until one arrives.
+  pub fn recv(&mut self, stream_id: StreamId) -> Result<Vec<u8>, String> {
+    loop {
+      match self.streams.get_mut(&stream_id) {
+        Some(stream) => {
+          if let Some(payload) = stream.inbound.pop_front() {
+            return Ok(payload);
+          }
+
+          if stream.closed {
+            return Err(format!("MuxConnection > Stream {} is closed", stream_id));
+          }
+        },
+
+        None => return Err(format!("MuxConnection > Stream {} does not exist", stream_id)),
+      }
+
+      self.pump()?;
+    }
+  }
+
+  /// Read one multiplexed frame off the underlying connection and
+  /// demultiplex it into the matching stream's inbound queue.
+  fn pump(&mut self) -> Result<(), String> {
+    let encoded = self.inner.wait_for_message()?;
+
+    let frame = base64::decode(encoded.trim())
+      .map_err(|err| format!("MuxConnection > Bad frame encoding: {}", err))?;
+
+    if frame.len() < 7 {
+      return Err(String::from("MuxConnection > Frame too short to contain a header"));
+    }
+
+    let stream_id = u16::from_be_bytes([frame[0], frame[1]]);
+    let flag = MuxFlag::from_byte(frame[2])?;
+    let len = u32::from_be_bytes([frame[3], frame[4], frame[5], frame[6]]) as usize;
+
+    if frame.len() != 7 + len {
+      return Err(String::from("MuxConnection > Frame length did not match its header"));
+    }
+
+    let payload = frame[7..].to_vec();
+
+    match flag {
+      MuxFlag::Syn => {
+        let kind = payload.first()
+          .copied()
+          .map(StreamKind::from_byte)
+          .ok_or_else(|| format!("MuxConnection > SYN frame for stream {} is missing its kind byte", stream_id))?;
+
+        self.streams.entry(stream_id).or_insert_with(|| MuxStream::new(kind));
+      },
+
+      MuxFlag::Fin => {
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+          stream.closed = true;
+        }
+      },
+
+      MuxFlag::Data => {
+        let stream = self.streams.entry(stream_id).or_insert_with(|| MuxStream::new(StreamKind::Other(0)));
+
+        // Cut this stream loose instead of erroring `pump()` itself.
+        if stream.closed {
+          // Already cut loose for overflowing (or FIN'd); discard.
+        } else if stream.inbound.len() >= MUX_MAX_QUEUED_FRAMES {
+          println!("MuxConnection > Stream {} inbound queue is full, closing just that stream", stream_id);
+          stream.closed = true;
+        } else {
+          stream.inbound.push_back(payload);
+        }
+      },
+    }
+
+    Ok(())
+  }
+
+  /// Frame and send a payload on `stream_id` through the underlying
+  /// connection, base64-encoded so it still fits through the string-based
+  /// `Connection` trait.
+  fn write_frame(&mut self, stream_id: StreamId, flag: MuxFlag, payload: &[u8]) -> Result<(), String> {
+    let mut frame = Vec::with_capacity(7 + payload.len());
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.push(flag.to_byte());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    if self.inner.send_message(&base64::encode(&frame)) {
+      Ok(())
+    } else {
+      Err(format!("MuxConnection > Failed to send frame on stream {}", stream_id))
+    }
+  }
+}
+
+impl<C: Connection> Connection for MuxConnection<C> {
+  /// Send on the reserved moves stream (0), so a `MuxConnection` can drop
+  /// in wherever a plain `Connection` is expected.
+  fn send_message(&mut self, message: &str) -> bool {
+    self.send(MUX_STREAM_MOVES, message.as_bytes()).is_ok()
+  }
+
+  /// Receive the next payload queued for the moves stream, pumping chat or
+  /// spectator frames into their own streams along the way.
+  fn wait_for_message(&mut self) -> Result<String, String> {
+    let payload = self.recv(MUX_STREAM_MOVES)?;
+
+    String::from_utf8(payload)
+      .map_err(|err| format!("MuxConnection > Moves stream frame was not valid UTF-8: {}", err))
+  }
+
+  fn get_message(&self) -> Result<String, String> {
+    self.inner.get_message()
+  }
+}
+
 // impl Connection for HttpConnection {
 //   fn send_message(&mut self, message: &str) -> bool {
 
 //   }
 // }
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use std::io::Cursor;
+
+  #[test]
+  fn test_frame_round_trip() {
+    let mut buffer = Vec::new();
+
+    write_frame(&mut buffer, b"make_move e2e4").unwrap();
+
+    let mut reader = Cursor::new(buffer);
+    let payload = read_frame(&mut reader).unwrap();
+
+    assert_eq!(payload, b"make_move e2e4");
+  }
+
+  #[test]
+  fn test_read_frame_truncated_header_is_err() {
+    let mut reader = Cursor::new(vec![0u8, 0u8]);
+
+    assert!(read_frame(&mut reader).is_err());
+  }
+
+  #[test]
+  fn test_read_frame_truncated_payload_is_err() {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(&10u32.to_be_bytes());
+    buffer.extend_from_slice(b"short");
+
+    let mut reader = Cursor::new(buffer);
+
+    assert!(read_frame(&mut reader).is_err());
+  }
+
+  #[test]
+  fn test_read_frame_rejects_oversized_length_before_allocating() {
+    let mut reader = Cursor::new((u32::MAX).to_be_bytes().to_vec());
+
+    assert!(read_frame(&mut reader).is_err());
+  }
+
+  #[test]
+  fn test_envelope_round_trip() {
+    let envelope = encode_envelope(7, "make_move e2e4");
+
+    assert_eq!(decode_envelope(&envelope).unwrap(), (7, String::from("make_move e2e4")));
+  }
+
+  #[test]
+  fn test_decode_envelope_rejects_missing_separator() {
+    assert!(decode_envelope("make_move e2e4").is_err());
+  }
+
+  #[test]
+  fn test_decode_envelope_rejects_non_numeric_sequence() {
+    assert!(decode_envelope("not_a_number:make_move e2e4").is_err());
+  }
+
+  #[test]
+  fn test_dedupe_catch_up_skips_every_queued_message_at_or_behind_it() {
+    // Two moves can be published in the window between subscribing and
+    // reading state_key, so more than one stale message can be queued up
+    // by the time wait_for_message first runs; state_key already reflects
+    // the newest of them, so all must be skipped, not just the first.
+    let mut catch_up_seq = Some(5);
+
+    assert!(dedupe_catch_up(&mut catch_up_seq, 4));
+    assert!(dedupe_catch_up(&mut catch_up_seq, 5));
+    assert_eq!(catch_up_seq, Some(5));
+  }
+
+  #[test]
+  fn test_dedupe_catch_up_forwards_a_later_move_even_if_its_text_repeats() {
+    let mut catch_up_seq = Some(3);
+
+    // A different ply that happens to produce the same move text as the
+    // catch-up snapshot must not be treated as the live echo of it.
+    assert!(!dedupe_catch_up(&mut catch_up_seq, 4));
+    assert_eq!(catch_up_seq, None);
+  }
+
+  #[test]
+  fn test_dedupe_catch_up_clears_once_a_newer_sequence_arrives() {
+    let mut catch_up_seq = Some(3);
+
+    assert!(dedupe_catch_up(&mut catch_up_seq, 3));
+    assert!(!dedupe_catch_up(&mut catch_up_seq, 4));
+    assert_eq!(catch_up_seq, None);
+  }
+
+  fn test_encrypted_connection() -> EncryptedConnection<EchoConnection> {
+    EncryptedConnection{
+      inner: EchoConnection::new(),
+      key: [7u8; 32],
+      send_nonce_base: [3u8; CHACHA_NONCE_LEN],
+      recv_nonce_base: [3u8; CHACHA_NONCE_LEN],
+      send_counter: 0,
+      recv_counter: 0,
+    }
+  }
+
+  #[test]
+  fn test_encrypted_connection_seal_open_round_trip() {
+    let connection = test_encrypted_connection();
+    let nonce = EncryptedConnection::<EchoConnection>::frame_nonce(&connection.send_nonce_base, 0);
+
+    let frame = connection.seal(b"make_move e2e4", &nonce);
+    let plaintext = connection.open(&frame, &nonce).unwrap();
+
+    assert_eq!(plaintext, b"make_move e2e4");
+  }
+
+  #[test]
+  fn test_encrypted_connection_open_rejects_tampered_ciphertext() {
+    let connection = test_encrypted_connection();
+    let nonce = EncryptedConnection::<EchoConnection>::frame_nonce(&connection.send_nonce_base, 0);
+
+    let mut frame = connection.seal(b"make_move e2e4", &nonce);
+    frame[CHACHA_NONCE_LEN] ^= 0xff;
+
+    assert!(connection.open(&frame, &nonce).is_err());
+  }
+
+  #[test]
+  fn test_encrypted_connection_open_rejects_ciphertext_truncated_past_a_trailing_zero_byte() {
+    let connection = test_encrypted_connection();
+    let nonce = EncryptedConnection::<EchoConnection>::frame_nonce(&connection.send_nonce_base, 0);
+
+    // Pick a plaintext whose last ciphertext byte is 0x00: recover the
+    // keystream byte at that position from a probe seal, then choose a
+    // final plaintext byte that XORs to zero. A MAC that only pads to a
+    // 16-byte boundary (no explicit length block) sees an identical
+    // padded block sequence whether or not that trailing zero byte is on
+    // the wire, so this is exactly the truncation the length block must
+    // catch.
+    let probe = connection.seal(b"make_move e2e4!", &nonce);
+    let keystream_last_byte = probe[probe.len() - POLY1305_TAG_LEN - 1] ^ b'!';
+
+    let mut plaintext = b"make_move e2e4".to_vec();
+    plaintext.push(keystream_last_byte);
+
+    let frame = connection.seal(&plaintext, &nonce);
+    let ciphertext_end = frame.len() - POLY1305_TAG_LEN;
+    assert_eq!(frame[ciphertext_end - 1], 0);
+
+    let mut truncated = frame.clone();
+    truncated.remove(ciphertext_end - 1);
+
+    assert!(connection.open(&truncated, &nonce).is_err());
+  }
+
+  #[test]
+  fn test_encrypted_connection_open_rejects_wrong_nonce() {
+    let connection = test_encrypted_connection();
+    let nonce = EncryptedConnection::<EchoConnection>::frame_nonce(&connection.send_nonce_base, 0);
+    let other_nonce = EncryptedConnection::<EchoConnection>::frame_nonce(&connection.send_nonce_base, 1);
+
+    let frame = connection.seal(b"make_move e2e4", &nonce);
+
+    assert!(connection.open(&frame, &other_nonce).is_err());
+  }
+
+  /// A `Connection` test double backed by a queue of pre-loaded inbound
+  /// messages, so `MuxConnection` tests can feed it frames to `pump()`
+  /// instead of relying on a real socket or `EchoConnection`'s fixed reply.
+  struct QueueConnection {
+    incoming: collections::VecDeque<String>,
+    sent: Vec<String>,
+  }
+
+  impl QueueConnection {
+    fn new() -> QueueConnection {
+      QueueConnection{incoming: collections::VecDeque::new(), sent: Vec::new()}
+    }
+
+    fn push(&mut self, message: String) {
+      self.incoming.push_back(message);
+    }
+  }
+
+  impl Connection for QueueConnection {
+    fn send_message(&mut self, message: &str) -> bool {
+      self.sent.push(String::from(message));
+      true
+    }
+
+    fn wait_for_message(&mut self) -> Result<String, String> {
+      self.incoming.pop_front()
+        .ok_or_else(|| String::from("QueueConnection > No more queued messages"))
+    }
+
+    fn get_message(&self) -> Result<String, String> {
+      Ok(String::from("Nothing"))
+    }
+  }
+
+  /// Relay every frame `sender` has written into `receiver`'s underlying
+  /// connection, as if it had arrived over the wire.
+  fn relay(sender: &mut MuxConnection<QueueConnection>, receiver: &mut MuxConnection<QueueConnection>) {
+    for frame in sender.inner.sent.drain(..) {
+      receiver.inner.push(frame);
+    }
+  }
+
+  #[test]
+  fn test_mux_syn_data_fin_round_trip_through_pump() {
+    let mut sender = MuxConnection::new(QueueConnection::new(), true);
+    let stream_id = sender.open_stream(StreamKind::Chat).unwrap();
+    sender.send(stream_id, b"chat hello").unwrap();
+    sender.close_stream(stream_id).unwrap();
+
+    let mut receiver = MuxConnection::new(QueueConnection::new(), false);
+    relay(&mut sender, &mut receiver);
+
+    // SYN, DATA, FIN: three frames to pump before the stream is drained and closed.
+    receiver.pump().unwrap();
+    assert_eq!(receiver.stream_kind(stream_id), Some(StreamKind::Chat));
+    receiver.pump().unwrap();
+    receiver.pump().unwrap();
+
+    assert_eq!(receiver.recv(stream_id).unwrap(), b"chat hello");
+    assert!(receiver.recv(stream_id).is_err());
+  }
+
+  #[test]
+  fn test_mux_stream_id_parity_prevents_collision_between_initiator_and_acceptor() {
+    let mut initiator = MuxConnection::new(QueueConnection::new(), true);
+    let mut acceptor = MuxConnection::new(QueueConnection::new(), false);
+
+    assert_eq!(initiator.open_stream(StreamKind::Chat).unwrap(), 1);
+    assert_eq!(initiator.open_stream(StreamKind::Spectator).unwrap(), 3);
+
+    assert_eq!(acceptor.open_stream(StreamKind::Chat).unwrap(), 2);
+    assert_eq!(acceptor.open_stream(StreamKind::Spectator).unwrap(), 4);
+  }
+
+  #[test]
+  fn test_mux_pump_closes_just_the_stream_whose_inbound_queue_is_full() {
+    let mut sender = MuxConnection::new(QueueConnection::new(), true);
+    let stream_id = sender.open_stream(StreamKind::Chat).unwrap();
+    sender.inner.sent.clear();
+
+    for _ in 0..MUX_MAX_QUEUED_FRAMES {
+      sender.send(stream_id, b"x").unwrap();
+    }
+
+    let mut receiver = MuxConnection::new(QueueConnection::new(), false);
+    relay(&mut sender, &mut receiver);
+
+    for _ in 0..MUX_MAX_QUEUED_FRAMES {
+      receiver.pump().unwrap();
+    }
+
+    sender.send(stream_id, b"one too many").unwrap();
+    relay(&mut sender, &mut receiver);
+
+    // The overflowing chat stream is closed rather than tearing down
+    // pump() itself...
+    receiver.pump().unwrap();
+    assert!(receiver.recv(stream_id).is_err());
+  }
+
+  #[test]
+  fn test_mux_overflowing_chat_stream_does_not_break_the_moves_stream() {
+    let mut sender = MuxConnection::new(QueueConnection::new(), true);
+    let chat_stream = sender.open_stream(StreamKind::Chat).unwrap();
+
+    for _ in 0..=MUX_MAX_QUEUED_FRAMES {
+      sender.send(chat_stream, b"x").unwrap();
+    }
+
+    sender.send(MUX_STREAM_MOVES, b"make_move e2e4").unwrap();
+
+    let mut receiver = MuxConnection::new(QueueConnection::new(), false);
+    relay(&mut sender, &mut receiver);
+
+    // ... and the reserved moves stream keeps working regardless of the
+    // chat stream's fate.
+    assert_eq!(receiver.recv(MUX_STREAM_MOVES).unwrap(), b"make_move e2e4");
+  }
+
+  #[test]
+  fn test_mux_syn_rejects_a_missing_kind_byte() {
+    let mut sender = MuxConnection::new(QueueConnection::new(), true);
+    sender.write_frame(1, MuxFlag::Syn, &[]).unwrap();
+
+    let mut receiver = MuxConnection::new(QueueConnection::new(), false);
+    relay(&mut sender, &mut receiver);
+
+    assert!(receiver.pump().is_err());
+  }
+}
@@ -0,0 +1,400 @@
+extern crate mio;
+
+use std::collections::HashMap;
+use std::io::{Read, Write, ErrorKind};
+use std::str;
+
+use mio::{Events, Poll, Token, Interest};
+use mio::net::{TcpListener, TcpStream};
+
+use connection::MAX_FRAME_PAYLOAD_BYTES;
+use protocol::Message;
+
+// Reserved for the listening socket; client sockets get tokens from 1 up.
+const SERVER_TOKEN: Token = Token(0);
+const EVENTS_CAPACITY: usize = 1024;
+const READ_CHUNK_BYTES: usize = 4096;
+
+/// Per-client buffered state: the raw socket, whatever bytes have been
+/// read but not yet decoded into a complete length-prefixed frame, and
+/// whatever bytes are queued to go out on the next writable event.
+struct ClientState {
+  stream: TcpStream,
+  inbound: Vec<u8>,
+  outbound: Vec<u8>,
+  session: Option<usize>,
+}
+
+/// A single chess game between two connected clients, identified by the
+/// tokens of the sockets playing it.
+struct GameSession {
+  white: Token,
+  black: Token,
+}
+
+/// Non-blocking, multi-game server
+pub struct GameServer {
+  poll: Poll,
+  listener: TcpListener,
+  clients: HashMap<Token, ClientState>,
+  sessions: Vec<GameSession>,
+  waiting: Option<Token>,
+  next_token: usize,
+}
+
+impl GameServer {
+  /// Bind `addr` and prepare the poll loop. Call `run` to start serving.
+  pub fn new(addr: &str) -> Result<GameServer, String> {
+    let socket_addr = addr.parse()
+      .map_err(|err| format!("GameServer > Bad listen address {}: {}", addr, err))?;
+
+    let mut listener = TcpListener::bind(socket_addr)
+      .map_err(|err| format!("GameServer > Could not bind to {}: {}", addr, err))?;
+
+    let poll = Poll::new()
+      .map_err(|err| format!("GameServer > Could not create poll: {}", err))?;
+
+    poll.registry()
+      .register(&mut listener, SERVER_TOKEN, Interest::READABLE)
+      .map_err(|err| format!("GameServer > Could not register listener: {}", err))?;
+
+    Ok(GameServer{
+      poll,
+      listener,
+      clients: HashMap::new(),
+      sessions: Vec::new(),
+      waiting: None,
+      next_token: 1,
+    })
+  }
+
+  /// Run the poll loop forever, accepting connections and dispatching
+  /// frames between matched clients.
+  pub fn run(&mut self) -> Result<(), String> {
+    let mut events = Events::with_capacity(EVENTS_CAPACITY);
+
+    loop {
+      self.poll.poll(&mut events, None)
+        .map_err(|err| format!("GameServer > Poll failed: {}", err))?;
+
+      let tokens: Vec<Token> = events.iter().map(|event| event.token()).collect();
+
+      for token in tokens {
+        if token == SERVER_TOKEN {
+          self.accept_clients()?;
+        } else {
+          self.handle_client_event(token);
+        }
+      }
+    }
+  }
+
+  /// Accept every pending connection on the listening socket, registering
+  /// each with the poll and feeding it into matchmaking.
+  fn accept_clients(&mut self) -> Result<(), String> {
+    loop {
+      let (mut stream, addr) = match self.listener.accept() {
+        Ok(pair) => pair,
+        Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+        Err(err) => return Err(format!("GameServer > Accept failed: {}", err)),
+      };
+
+      println!("GameServer > Client connected from: {}", addr);
+
+      let token = Token(self.next_token);
+      self.next_token += 1;
+
+      self.poll.registry()
+        .register(&mut stream, token, Interest::READABLE | Interest::WRITABLE)
+        .map_err(|err| format!("GameServer > Could not register client: {}", err))?;
+
+      self.clients.insert(token, ClientState{
+        stream,
+        inbound: Vec::new(),
+        outbound: Vec::new(),
+        session: None,
+      });
+
+      self.matchmake(token);
+    }
+  }
+
+  /// Pair two waiting clients into a session, or park this one as waiting
+  /// for the next one to show up.
+  fn matchmake(&mut self, token: Token) {
+    match self.waiting.take() {
+      Some(opponent) => {
+        let session_index = self.sessions.len();
+        self.sessions.push(GameSession{white: opponent, black: token});
+
+        if let Some(client) = self.clients.get_mut(&opponent) {
+          client.session = Some(session_index);
+        }
+
+        if let Some(client) = self.clients.get_mut(&token) {
+          client.session = Some(session_index);
+        }
+
+        println!("GameServer > Matched session {} ({:?} vs {:?})", session_index, opponent, token);
+      },
+
+      None => self.waiting = Some(token),
+    }
+  }
+
+  /// Drain whatever is readable for a client, extract complete frames and
+  /// dispatch them, then flush whatever is pending for a writable client.
+  fn handle_client_event(&mut self, token: Token) {
+    self.read_available(token);
+    self.flush_outbound(token);
+  }
+
+  /// Read everything currently available without blocking, appending it to
+  /// the client's inbound buffer.
+  fn read_available(&mut self, token: Token) {
+    let mut closed = false;
+
+    if let Some(client) = self.clients.get_mut(&token) {
+      let mut chunk = [0u8; READ_CHUNK_BYTES];
+
+      loop {
+        match client.stream.read(&mut chunk) {
+          Ok(0) => {
+            closed = true;
+            break;
+          },
+          Ok(count) => client.inbound.extend_from_slice(&chunk[..count]),
+          Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+          Err(err) => {
+            println!("GameServer > Read error for {:?}: {}", token, err);
+            closed = true;
+            break;
+          },
+        }
+      }
+    }
+
+    if closed {
+      self.disconnect(token);
+      return;
+    }
+
+    self.dispatch_frames(token);
+  }
+
+  /// Pull every complete length-prefixed frame out of the client's inbound
+  /// buffer, decode it into a `protocol::Message`, and either act on it
+  /// directly (`bye` tears the session down) or route it to the other
+  /// player in its session.
+  fn dispatch_frames(&mut self, token: Token) {
+    loop {
+      let frame = match self.clients.get_mut(&token) {
+        Some(client) => match take_frame(&mut client.inbound) {
+          Ok(Some(frame)) => frame,
+          Ok(None) => return,
+          Err(err) => {
+            println!("GameServer > {:?}: {}", token, err);
+            self.disconnect(token);
+            return;
+          },
+        },
+        None => return,
+      };
+
+      let text = match str::from_utf8(&frame) {
+        Ok(text) => text,
+        Err(err) => {
+          println!("GameServer > Bad frame from {:?}: {}", token, err);
+          continue;
+        },
+      };
+
+      let message = parse_message(text);
+
+      match message {
+        Message::Bye => {
+          self.disconnect(token);
+          return;
+        },
+        _ => self.route_message(token, text),
+      }
+    }
+  }
+
+  /// Forward a decoded message to the other player in the sender's session and flush it immediately.
+  fn route_message(&mut self, token: Token, message: &str) {
+    let session_index = match self.clients.get(&token).and_then(|client| client.session) {
+      Some(index) => index,
+      None => return,
+    };
+
+    let session = &self.sessions[session_index];
+    let opponent = if session.white == token { session.black } else { session.white };
+
+    if let Some(client) = self.clients.get_mut(&opponent) {
+      client.outbound.extend_from_slice(&(message.len() as u32).to_be_bytes());
+      client.outbound.extend_from_slice(message.as_bytes());
+    }
+
+    self.flush_outbound(opponent);
+  }
+
+  /// Write as much of the client's outbound buffer as the socket accepts.
+  fn flush_outbound(&mut self, token: Token) {
+    let mut closed = false;
+
+    if let Some(client) = self.clients.get_mut(&token) {
+      if client.outbound.is_empty() {
+        return;
+      }
+
+      match client.stream.write(&client.outbound) {
+        Ok(count) => { client.outbound.drain(..count); },
+        Err(err) if err.kind() == ErrorKind::WouldBlock => (),
+        Err(err) => {
+          println!("GameServer > Write error for {:?}: {}", token, err);
+          closed = true;
+        },
+      }
+    }
+
+    if closed {
+      self.disconnect(token);
+    }
+  }
+
+  /// Deregister and drop a client, notifying and unlinking its opponent if it was mid-game.
+  fn disconnect(&mut self, token: Token) {
+    if let Some(session_index) = self.clients.get(&token).and_then(|client| client.session) {
+      let session = &self.sessions[session_index];
+      let opponent = if session.white == token { session.black } else { session.white };
+
+      if let Some(opponent_client) = self.clients.get_mut(&opponent) {
+        let notice = Message::Bye.to_string();
+        opponent_client.outbound.extend_from_slice(&(notice.len() as u32).to_be_bytes());
+        opponent_client.outbound.extend_from_slice(notice.as_bytes());
+        opponent_client.session = None;
+      }
+
+      self.flush_outbound(opponent);
+    }
+
+    if let Some(mut client) = self.clients.remove(&token) {
+      let _ = self.poll.registry().deregister(&mut client.stream);
+    }
+
+    if self.waiting == Some(token) {
+      self.waiting = None;
+    }
+
+    println!("GameServer > Client {:?} disconnected", token);
+  }
+}
+
+/// Decode the leading command word of a frame into the `protocol::Message` it names.
+fn parse_message(text: &str) -> Message {
+  let command = text.split_whitespace().next().unwrap_or("");
+
+  if command == Message::Bye.to_string() {
+    Message::Bye
+  } else if command == Message::MakeMove.to_string() {
+    Message::MakeMove
+  } else {
+    Message::BadMessage
+  }
+}
+
+/// Pop one complete length-prefixed frame off the front of `buffer`, if one is fully present.
+fn take_frame(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+  if buffer.len() < 4 {
+    return Ok(None);
+  }
+
+  let len = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+
+  if len > MAX_FRAME_PAYLOAD_BYTES {
+    return Err(format!("Frame of {} bytes exceeds the {}-byte limit", len, MAX_FRAME_PAYLOAD_BYTES));
+  }
+
+  if buffer.len() < 4 + len {
+    return Ok(None);
+  }
+
+  let frame = buffer[4..4 + len].to_vec();
+  buffer.drain(..4 + len);
+
+  Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn framed(payload: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(payload);
+    buffer
+  }
+
+  #[test]
+  fn test_take_frame_partial_header_returns_none() {
+    let mut buffer = vec![0u8, 0u8];
+
+    assert_eq!(take_frame(&mut buffer).unwrap(), None);
+    assert_eq!(buffer.len(), 2);
+  }
+
+  #[test]
+  fn test_take_frame_partial_payload_returns_none() {
+    let mut buffer = framed(b"make_move e2e4");
+    buffer.truncate(buffer.len() - 1);
+    let expected = buffer.clone();
+
+    assert_eq!(take_frame(&mut buffer).unwrap(), None);
+    assert_eq!(buffer, expected);
+  }
+
+  #[test]
+  fn test_take_frame_exact_boundary_returns_the_frame_and_drains_the_buffer() {
+    let mut buffer = framed(b"make_move e2e4");
+
+    assert_eq!(take_frame(&mut buffer).unwrap(), Some(b"make_move e2e4".to_vec()));
+    assert!(buffer.is_empty());
+  }
+
+  #[test]
+  fn test_take_frame_returns_only_the_first_of_several_frames_in_one_buffer() {
+    let mut buffer = framed(b"make_move e2e4");
+    buffer.extend_from_slice(&framed(b"bye"));
+
+    assert_eq!(take_frame(&mut buffer).unwrap(), Some(b"make_move e2e4".to_vec()));
+    assert_eq!(take_frame(&mut buffer).unwrap(), Some(b"bye".to_vec()));
+    assert!(buffer.is_empty());
+  }
+
+  #[test]
+  fn test_take_frame_rejects_oversized_length_before_buffering() {
+    let mut buffer = (u32::MAX).to_be_bytes().to_vec();
+
+    assert!(take_frame(&mut buffer).is_err());
+  }
+
+  #[test]
+  fn test_parse_message_recognizes_bye() {
+    assert!(matches!(parse_message(&Message::Bye.to_string()), Message::Bye));
+  }
+
+  #[test]
+  fn test_parse_message_recognizes_make_move_with_trailing_args() {
+    let text = format!("{} e2e4", Message::MakeMove.to_string());
+
+    assert!(matches!(parse_message(&text), Message::MakeMove));
+  }
+
+  #[test]
+  fn test_parse_message_defaults_to_bad_message() {
+    assert!(matches!(parse_message("not_a_real_command"), Message::BadMessage));
+  }
+}
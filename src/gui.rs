@@ -22,6 +22,9 @@ use std::mem;
 use std::os::raw::c_void;
 
 
+// Each vertex is position (3) + color (4) + normal (3)
+const VERTEX_STRIDE_FLOATS: GLsizei = 10;
+
 const vertex_shader_source: &str = r#"
     #version 330 core
 
@@ -35,27 +38,44 @@ const vertex_shader_source: &str = r#"
     layout (location = 2) in vec3 normal;
 
     out vec3 normal_vertex;
+    out vec3 frag_pos_vertex;
     out vec4 color_vertex;
 
     void main() {
-       gl_Position = projection * view * model * vec4(position.x, position.y, position.z, 1.0);
+       vec4 world_position = model * vec4(position.x, position.y, position.z, 1.0);
+
+       gl_Position = projection * view * world_position;
 
-       /* Pass along the color and the normal for lighting. */
+       /* Pass along the color, world-space position and normal for lighting. */
        color_vertex = color;
-       normal_vertex = normal;
+       frag_pos_vertex = world_position.xyz;
+       normal_vertex = mat3(model) * normal;
     }
 "#;
 
+// Ambient + Lambertian diffuse only; no specular term, since nothing in
+// this file tracks a camera/eye position to compute one against.
 const fragment_shader_source: &str = r#"
     #version 330 core
 
     in vec3 normal_vertex;
+    in vec3 frag_pos_vertex;
     in vec4 color_vertex;
 
+    uniform vec3 light_position;
+
     out vec4 color_out;
 
     void main() {
-       color_out = color_vertex;
+       vec3 normal = normalize(normal_vertex);
+       vec3 light_direction = normalize(light_position - frag_pos_vertex);
+
+       float ambient_strength = 0.2;
+       float diffuse_strength = max(dot(normal, light_direction), 0.0);
+
+       vec3 lighting = vec3(ambient_strength + diffuse_strength);
+
+       color_out = vec4(lighting, 1.0) * color_vertex;
     }
 "#;
 
@@ -67,6 +87,7 @@ pub struct Window {
   events: Box<Receiver<(f64, glfw::WindowEvent)>>,
   program: GLuint,
   vaos: HashMap<GLuint, usize>, // VAO --> number of points
+  light_position: Vector3<f32>,
 }
 
 impl Window {
@@ -88,8 +109,11 @@ impl Window {
       events,
       program,
       vaos: HashMap::new(),
+      light_position: Vector3::new(2.0f32, 2.0f32, 2.0f32),
     };
 
+    window.set_vec3("light_position", window.light_position);
+
     let grid = window.draw_grid();
     let pawn = window.draw_pawn();
 
@@ -225,6 +249,16 @@ impl Window {
     }
   }
 
+  // Sets the uniform with a vec3
+  fn set_vec3(&self, name: &str, vec: Vector3<f32>) {
+    let uniform_name_c_str = CString::new(name).unwrap();
+
+    unsafe {
+      gl::UseProgram(self.program);
+      gl::Uniform3f(gl::GetUniformLocation(self.program, uniform_name_c_str.as_ptr()), vec.x, vec.y, vec.z);
+    }
+  }
+
   /// Couldn't find that in the docs for cgmath
   fn get_identity_mat4() -> Matrix4<f32> {
     Matrix4::from_cols(
@@ -259,8 +293,12 @@ impl Window {
       vec![],
     );
 
+    // The grid is flat in the XY plane, so every point shares the same
+    // up-facing normal.
+    let normal = Vector3::new(0.0f32, 0.0f32, 1.0f32);
+
     // Helps add points to a vector
-    let add_points = |points: &Vector3<f32>, color: &[f32], destination: &mut Vec<f32>| {
+    let add_points = |points: &Vector3<f32>, color: &[f32], normal: &Vector3<f32>, destination: &mut Vec<f32>| {
       destination.push(points.x);
       destination.push(points.y);
       destination.push(points.z);
@@ -269,6 +307,10 @@ impl Window {
       destination.push(color[1]);
       destination.push(color[2]);
       destination.push(color[3]);
+
+      destination.push(normal.x);
+      destination.push(normal.y);
+      destination.push(normal.z);
     };
 
     // Indice counter
@@ -305,10 +347,10 @@ impl Window {
         // Increment square counter
         sc += 1;
 
-        add_points(&p1, &color, &mut points);
-        add_points(&p2, &color, &mut points);
-        add_points(&p3, &color, &mut points);
-        add_points(&p4, &color, &mut points);
+        add_points(&p1, &color, &normal, &mut points);
+        add_points(&p2, &color, &normal, &mut points);
+        add_points(&p3, &color, &normal, &mut points);
+        add_points(&p4, &color, &normal, &mut points);
 
         // Indices
         indices.push(ic);
@@ -372,13 +414,18 @@ impl Window {
         gl::STATIC_DRAW,
       );
 
-      // Enable the points and the colors in the vertex shader
-      gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 7 * mem::size_of::<GLfloat>() as GLsizei, ptr::null());
+      // Enable the points, the colors and the normals in the vertex shader
+      let stride = VERTEX_STRIDE_FLOATS * mem::size_of::<GLfloat>() as GLsizei;
+
+      gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
       gl::EnableVertexAttribArray(0);
 
-      gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 7 * mem::size_of::<GLfloat>() as GLsizei, (3 * mem::size_of::<GLfloat>()) as *const c_void);
+      gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, stride, (3 * mem::size_of::<GLfloat>()) as *const c_void);
       gl::EnableVertexAttribArray(1);
 
+      gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, stride, (7 * mem::size_of::<GLfloat>()) as *const c_void);
+      gl::EnableVertexAttribArray(2);
+
       // Unbind the VBO, but keep the EBO bound
       gl::BindBuffer(gl::ARRAY_BUFFER, 0);
 
@@ -399,10 +446,11 @@ impl Window {
     
     // transform.transform_vector(p1);
 
+    // Facing the camera along +z, like the grid.
     let triangle = vec![
-      0.1f32, -0.1f32, -1.0f32, 1.0f32, 1.0f32, 0.0f32, 1.0f32,
-      0.0f32, 0.1f32, -1.0f32, 1.0f32, 1.0f32, 0.0f32, 1.0f32,
-      -0.1f32, -0.1f32, -1.0f32, 1.0f32, 1.0f32, 0.0f32, 1.0f32,
+      0.1f32, -0.1f32, -1.0f32, 1.0f32, 1.0f32, 0.0f32, 1.0f32, 0.0f32, 0.0f32, 1.0f32,
+      0.0f32, 0.1f32, -1.0f32, 1.0f32, 1.0f32, 0.0f32, 1.0f32, 0.0f32, 0.0f32, 1.0f32,
+      -0.1f32, -0.1f32, -1.0f32, 1.0f32, 1.0f32, 0.0f32, 1.0f32, 0.0f32, 0.0f32, 1.0f32,
     ];
 
     let indices = vec![0, 1, 2];
@@ -418,6 +466,8 @@ impl Window {
   }
 
   pub fn draw(&mut self) {
+    self.set_vec3("light_position", self.light_position);
+
     unsafe {
       gl::ClearColor(0.2, 0.3, 0.3, 1.0);
       gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);